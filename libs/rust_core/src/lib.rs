@@ -1,4 +1,5 @@
 use pyo3::prelude::*;
+use pyo3::types::PyDict;
 
 fn is_nan(val: f64) -> bool {
     val.is_nan()
@@ -32,6 +33,75 @@ fn rolling_mean(values: &[f64], window: usize) -> Vec<f64> {
     out
 }
 
+fn rolling_std(values: &[f64], period: usize) -> Vec<f64> {
+    let n = values.len();
+    let mut out = vec![f64::NAN; n];
+    if period == 0 || n == 0 {
+        return out;
+    }
+    for i in 0..n {
+        if i + 1 >= period {
+            let slice = &values[i + 1 - period..=i];
+            let mut sum = 0.0;
+            let mut count = 0;
+            for v in slice {
+                if !is_nan(*v) {
+                    sum += v;
+                    count += 1;
+                }
+            }
+            if count > 0 {
+                let mean = sum / count as f64;
+                let mut sum_sq_diff = 0.0;
+                for v in slice {
+                    if !is_nan(*v) {
+                        sum_sq_diff += (v - mean).powi(2);
+                    }
+                }
+                // Sample standard deviation (divide by N-1, unless N=1)
+                if count > 1 {
+                    out[i] = (sum_sq_diff / (count as f64 - 1.0)).sqrt();
+                } else {
+                    out[i] = 0.0;
+                }
+            }
+        }
+    }
+    out
+}
+
+fn wilder_rma(values: &[f64], period: usize) -> Vec<f64> {
+    let n = values.len();
+    let mut out = vec![f64::NAN; n];
+    if period == 0 || n == 0 {
+        return out;
+    }
+    // 用前 period 个有效值的简单平均来播种，随后递归平滑：
+    // rma[i] = rma[i-1] + (x[i] - rma[i-1]) / period
+    let mut sum = 0.0;
+    let mut count: usize = 0;
+    let mut prev = f64::NAN;
+    for i in 0..n {
+        let v = values[i];
+        if is_nan(prev) {
+            if !is_nan(v) {
+                sum += v;
+                count += 1;
+            }
+            if count >= period {
+                prev = sum / period as f64;
+                out[i] = prev;
+            }
+        } else if !is_nan(v) {
+            prev += (v - prev) / period as f64;
+            out[i] = prev;
+        } else {
+            out[i] = prev;
+        }
+    }
+    out
+}
+
 fn ema_series(values: &[f64], period: usize) -> Vec<f64> {
     let n = values.len();
     let mut out = vec![f64::NAN; n];
@@ -84,12 +154,26 @@ fn ma(values: Vec<f64>, window: usize) -> PyResult<Vec<f64>> {
     Ok(out)
 }
 
-/// 计算 RSI（SMA 版本）。
+/// 根据 smoothing 选择平滑方式对序列做滚动平均。
+/// - "sma": 简单移动平均（rolling_mean）
+/// - "wilder": Wilder 递归平滑（wilder_rma）
+fn smooth(values: &[f64], period: usize, smoothing: &str) -> PyResult<Vec<f64>> {
+    match smoothing {
+        "sma" => Ok(rolling_mean(values, period)),
+        "wilder" => Ok(wilder_rma(values, period)),
+        _ => Err(pyo3::exceptions::PyValueError::new_err(
+            "smoothing 必须为 \"sma\" 或 \"wilder\"",
+        )),
+    }
+}
+
+/// 计算 RSI。
 /// - values: 输入序列
 /// - period: 周期长度（必须 > 0）
+/// - smoothing: 平均方式，"sma"（简单移动平均）或 "wilder"（Wilder 递归平滑）
 /// 返回与输入等长的序列，前 period 个位置为 NaN。
 #[pyfunction]
-fn rsi(values: Vec<f64>, period: usize) -> PyResult<Vec<f64>> {
+fn rsi(values: Vec<f64>, period: usize, smoothing: &str) -> PyResult<Vec<f64>> {
     if period == 0 {
         return Err(pyo3::exceptions::PyValueError::new_err(
             "period 必须大于 0",
@@ -112,8 +196,8 @@ fn rsi(values: Vec<f64>, period: usize) -> PyResult<Vec<f64>> {
         }
     }
 
-    let avg_gain = rolling_mean(&gains, period);
-    let avg_loss = rolling_mean(&losses, period);
+    let avg_gain = smooth(&gains, period, smoothing)?;
+    let avg_loss = smooth(&losses, period, smoothing)?;
 
     let mut out = vec![f64::NAN; n];
     for i in 0..n {
@@ -136,8 +220,9 @@ fn rsi(values: Vec<f64>, period: usize) -> PyResult<Vec<f64>> {
 /// - low: 最低价序列
 /// - close: 收盘价序列
 /// - period: 周期长度（必须 > 0）
+/// - smoothing: 平均方式，"sma"（简单移动平均）或 "wilder"（Wilder 递归平滑）
 #[pyfunction]
-fn atr(high: Vec<f64>, low: Vec<f64>, close: Vec<f64>, period: usize) -> PyResult<Vec<f64>> {
+fn atr(high: Vec<f64>, low: Vec<f64>, close: Vec<f64>, period: usize, smoothing: &str) -> PyResult<Vec<f64>> {
     if period == 0 {
         return Err(pyo3::exceptions::PyValueError::new_err(
             "period 必须大于 0",
@@ -166,7 +251,137 @@ fn atr(high: Vec<f64>, low: Vec<f64>, close: Vec<f64>, period: usize) -> PyResul
         tr[i] = max_val;
     }
 
-    Ok(rolling_mean(&tr, period))
+    smooth(&tr, period, smoothing)
+}
+
+/// 计算 ADX / +DI / -DI 方向性运动指标。
+/// - high/low/close: 价格序列
+/// - period: 周期长度（必须 > 0）
+/// 返回 (adx, plus_di, minus_di)，预热区间为 NaN。
+/// +DM/-DM 与 TR 均使用 Wilder 递归平滑；TR 为 0 时该位置 +DI/-DI 为 NaN，
+/// TR 或 DI 之和为 0 时该位置 DX 为 NaN，ADX 在该位置沿用上一值（播种前为 NaN）。
+#[pyfunction]
+fn adx(high: Vec<f64>, low: Vec<f64>, close: Vec<f64>, period: usize) -> PyResult<(Vec<f64>, Vec<f64>, Vec<f64>)> {
+    if period == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "period 必须大于 0",
+        ));
+    }
+    let n = high.len().min(low.len()).min(close.len());
+    let mut tr = vec![f64::NAN; n];
+    let mut plus_dm = vec![f64::NAN; n];
+    let mut minus_dm = vec![f64::NAN; n];
+    for i in 0..n {
+        let h = high[i];
+        let l = low[i];
+        if i == 0 {
+            tr[i] = h - l;
+            plus_dm[i] = 0.0;
+            minus_dm[i] = 0.0;
+            continue;
+        }
+        let prev_close = close[i - 1];
+        let tr1 = h - l;
+        let tr2 = (h - prev_close).abs();
+        let tr3 = (l - prev_close).abs();
+        let mut max_val = tr1;
+        if !tr2.is_nan() && tr2 > max_val {
+            max_val = tr2;
+        }
+        if !tr3.is_nan() && tr3 > max_val {
+            max_val = tr3;
+        }
+        tr[i] = max_val;
+
+        let up_move = h - high[i - 1];
+        let down_move = low[i - 1] - l;
+        plus_dm[i] = if up_move > down_move && up_move > 0.0 { up_move } else { 0.0 };
+        minus_dm[i] = if down_move > up_move && down_move > 0.0 { down_move } else { 0.0 };
+    }
+
+    let sm_tr = wilder_rma(&tr, period);
+    let sm_plus = wilder_rma(&plus_dm, period);
+    let sm_minus = wilder_rma(&minus_dm, period);
+
+    let mut plus_di = vec![f64::NAN; n];
+    let mut minus_di = vec![f64::NAN; n];
+    let mut dx = vec![f64::NAN; n];
+    for i in 0..n {
+        let t = sm_tr[i];
+        if is_nan(t) || t == 0.0 {
+            continue;
+        }
+        let pdi = 100.0 * sm_plus[i] / t;
+        let mdi = 100.0 * sm_minus[i] / t;
+        plus_di[i] = pdi;
+        minus_di[i] = mdi;
+        let di_sum = pdi + mdi;
+        if di_sum != 0.0 {
+            dx[i] = 100.0 * (pdi - mdi).abs() / di_sum;
+        }
+    }
+
+    let adx = wilder_rma(&dx, period);
+    Ok((adx, plus_di, minus_di))
+}
+
+/// 计算 KDJ 随机振荡指标。
+/// - high/low/close: 价格序列
+/// - n: RSV 的回看窗口（必须 > 0）
+/// - k_period/d_period: K、D 的平滑周期（必须 > 0，常用 9/3/3）
+/// 返回 (k, d, j)。不足 n 根 K 线时 RSV 为 NaN；若窗口内最高价等于最低价，
+/// 则沿用上一根 RSV（初始为 50）。K、D 以 50 为种子按经典递推平滑，J = 3K - 2D。
+#[pyfunction]
+fn kdj(
+    high: Vec<f64>,
+    low: Vec<f64>,
+    close: Vec<f64>,
+    n: usize,
+    k_period: usize,
+    d_period: usize,
+) -> PyResult<(Vec<f64>, Vec<f64>, Vec<f64>)> {
+    if n == 0 || k_period == 0 || d_period == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "n、k_period、d_period 必须大于 0",
+        ));
+    }
+    let len = high.len().min(low.len()).min(close.len());
+    let mut k = vec![f64::NAN; len];
+    let mut d = vec![f64::NAN; len];
+    let mut j = vec![f64::NAN; len];
+
+    let k_alpha = 1.0 / k_period as f64;
+    let d_alpha = 1.0 / d_period as f64;
+    let mut prev_k = 50.0;
+    let mut prev_d = 50.0;
+    let mut prev_rsv = 50.0;
+
+    for i in 0..len {
+        if i + 1 < n {
+            continue;
+        }
+        let window_hi = &high[i + 1 - n..=i];
+        let window_lo = &low[i + 1 - n..=i];
+        let hn = window_hi.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let ln = window_lo.iter().cloned().fold(f64::INFINITY, f64::min);
+        let rsv = if hn == ln {
+            prev_rsv
+        } else {
+            (close[i] - ln) / (hn - ln) * 100.0
+        };
+        prev_rsv = rsv;
+
+        let kv = prev_k * (1.0 - k_alpha) + rsv * k_alpha;
+        let dv = prev_d * (1.0 - d_alpha) + kv * d_alpha;
+        prev_k = kv;
+        prev_d = dv;
+
+        k[i] = kv;
+        d[i] = dv;
+        j[i] = 3.0 * kv - 2.0 * dv;
+    }
+
+    Ok((k, d, j))
 }
 
 /// 计算滚动标准差。
@@ -179,48 +394,35 @@ fn stddev(values: Vec<f64>, period: usize) -> PyResult<Vec<f64>> {
             "period 必须大于 0",
         ));
     }
-    let n = values.len();
-    let mut out = vec![f64::NAN; n];
-    if n == 0 {
-        return Ok(out);
+    Ok(rolling_std(&values, period))
+}
+
+/// 计算布林带（Bollinger Bands）。
+/// - values: 输入序列
+/// - period: 窗口长度（必须 > 0），用于中轨 SMA 与滚动标准差
+/// - mult: 上下轨相对标准差的倍数（常用 2.0）
+/// 返回 (middle, upper, lower) 三条等长序列，预热区间均为 NaN 且逐元素对齐。
+#[pyfunction]
+fn bbands(values: Vec<f64>, period: usize, mult: f64) -> PyResult<(Vec<f64>, Vec<f64>, Vec<f64>)> {
+    if period == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "period 必须大于 0",
+        ));
     }
-    
-    // Welford's algorithm or Naive two-pass? 
-    // For simplicity and vector speed, let's use the naive rolling window sum of squares approach
-    // Var = E[X^2] - (E[X])^2
-    // But precision issues might arise.
-    // Let's stick to a simple loop for clarity and safety first.
-    
-    // Rolling variance
+    let n = values.len();
+    let mid = rolling_mean(&values, period);
+    let sd = rolling_std(&values, period);
+    let mut upper = vec![f64::NAN; n];
+    let mut lower = vec![f64::NAN; n];
     for i in 0..n {
-        if i + 1 >= period {
-            let slice = &values[i + 1 - period..=i];
-            let mut sum = 0.0;
-            let mut count = 0;
-            for v in slice {
-                if !is_nan(*v) {
-                    sum += v;
-                    count += 1;
-                }
-            }
-            if count > 0 {
-                let mean = sum / count as f64;
-                let mut sum_sq_diff = 0.0;
-                for v in slice {
-                    if !is_nan(*v) {
-                        sum_sq_diff += (v - mean).powi(2);
-                    }
-                }
-                // Sample standard deviation (divide by N-1, unless N=1)
-                if count > 1 {
-                    out[i] = (sum_sq_diff / (count as f64 - 1.0)).sqrt();
-                } else {
-                     out[i] = 0.0;
-                }
-            }
+        let m = mid[i];
+        let s = sd[i];
+        if !is_nan(m) && !is_nan(s) {
+            upper[i] = m + mult * s;
+            lower[i] = m - mult * s;
         }
     }
-    Ok(out)
+    Ok((mid, upper, lower))
 }
 
 /// 计算 EMA（指数移动平均）。
@@ -236,6 +438,75 @@ fn ema(values: Vec<f64>, period: usize) -> PyResult<Vec<f64>> {
     Ok(ema_series(&values, period))
 }
 
+/// 按信号撮合一次开/平/反手（供 `simulate_trades_v2` 在 close 或 next_open 两种时点复用）。
+/// fill_px 为本次成交的参考价（当根 Close 或下一根 Open），滑点在其基础上向不利方向调整。
+#[allow(clippy::too_many_arguments)]
+fn execute_signal(
+    sig: i32,
+    fill_px: f64,
+    hi: f64,
+    lo: f64,
+    ts: i64,
+    bar: usize,
+    allow_short: bool,
+    commission_pct: f64,
+    slippage_pct: f64,
+    position_size: &mut f64,
+    entry_price: &mut f64,
+    entry_ts: &mut i64,
+    entry_bar: &mut usize,
+    qty: &mut f64,
+    entry_commission: &mut f64,
+    entry_slippage: &mut f64,
+    highest_high: &mut f64,
+    lowest_low: &mut f64,
+    cash: &mut f64,
+    trades: &mut Vec<(i64, i64, f64, f64, f64, f64, f64, String)>,
+) {
+    if sig == 0 {
+        return;
+    }
+    // 如果有反向持仓，先平仓
+    if *position_size != 0.0 && ((sig == 1 && *position_size == -1.0) || (sig == -1 && *position_size == 1.0)) {
+        let exit_fill = if *position_size > 0.0 {
+            fill_px * (1.0 - slippage_pct)
+        } else {
+            fill_px * (1.0 + slippage_pct)
+        };
+        let gross = (exit_fill - *entry_price) * *position_size * *qty;
+        let exit_commission = commission_pct * (*qty * exit_fill).abs();
+        let exit_slippage = *qty * fill_px * slippage_pct;
+        *cash += gross - exit_commission;
+        let commission = *entry_commission + exit_commission;
+        let slippage = *entry_slippage + exit_slippage;
+        let pnl = gross - commission;
+        trades.push((*entry_ts, ts, *entry_price, exit_fill, pnl, commission, slippage, "signal_flip".to_string()));
+        *position_size = 0.0;
+        *qty = 0.0;
+    }
+
+    // 开新仓：用当前可用资金定量，成交价含滑点，开仓计提手续费
+    if *position_size == 0.0 && (sig == 1 || (sig == -1 && allow_short)) {
+        let dir = if sig == 1 { 1.0 } else { -1.0 };
+        let fill = if dir > 0.0 {
+            fill_px * (1.0 + slippage_pct)
+        } else {
+            fill_px * (1.0 - slippage_pct)
+        };
+        let notional = *cash;
+        *qty = if fill != 0.0 { notional / fill } else { 0.0 };
+        *entry_commission = commission_pct * notional;
+        *entry_slippage = *qty * fill_px * slippage_pct;
+        *cash -= *entry_commission;
+        *position_size = dir;
+        *entry_price = fill;
+        *entry_ts = ts;
+        *entry_bar = bar;
+        *highest_high = hi;
+        *lowest_low = lo;
+    }
+}
+
 /// 模拟交易执行 (支持 SL/TP 和 path-dependence)。
 ///
 /// Parameters
@@ -248,13 +519,20 @@ fn ema(values: Vec<f64>, period: usize) -> PyResult<Vec<f64>> {
 /// signals: 信号序列 (1=Buy, -1=Sell, 0=None)
 /// sl_pct: 止损百分比 (e.g., 0.05 for 5%)
 /// tp_pct: 止盈百分比 (e.g., 0.10 for 10%)
+/// allow_short: 是否允许做空
+/// initial_cash: 初始资金
+/// commission_pct: 手续费率 (按成交额计，开/平仓各收一次)
+/// slippage_pct: 滑点率 (买入成交价上浮、卖出成交价下浮，对持仓方向不利)
+/// trail_pct: 移动止盈百分比 (0 表示关闭)；多头以建仓后最高价 * (1 - trail_pct) 为动态止损，空头镜像处理
+/// execution: 撮合时点，"close"（信号在当根 Close 成交）或 "next_open"（信号在下一根 Open 成交，避免未来函数）
 ///
 /// Returns
 /// -------
 /// (equity_curve, trades_list)
 /// equity_curve: Vec<(ts, equity)>
-/// trades_list: Vec<(entry_ts, exit_ts, entry_price, exit_price, pnl, reason)>
+/// trades_list: Vec<(entry_ts, exit_ts, entry_price, exit_price, pnl, commission, slippage, reason)>
 #[pyfunction]
+#[allow(clippy::too_many_arguments)]
 fn simulate_trades_v2(
     timestamps: Vec<i64>,
     opens: Vec<f64>,
@@ -265,23 +543,43 @@ fn simulate_trades_v2(
     sl_pct: f64,
     tp_pct: f64,
     allow_short: bool,
-) -> PyResult<(Vec<(i64, f64)>, Vec<(i64, i64, f64, f64, f64, String)>)> {
+    initial_cash: f64,
+    commission_pct: f64,
+    slippage_pct: f64,
+    trail_pct: f64,
+    execution: &str,
+) -> PyResult<(Vec<(i64, f64)>, Vec<(i64, i64, f64, f64, f64, f64, f64, String)>)> {
     let n = timestamps.len();
     if opens.len() != n || highs.len() != n || lows.len() != n || closes.len() != n || signals.len() != n {
         return Err(pyo3::exceptions::PyValueError::new_err(
             "All input arrays must have the same length",
         ));
     }
+    let next_open = match execution {
+        "close" => false,
+        "next_open" => true,
+        _ => {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "execution 必须为 \"close\" 或 \"next_open\"",
+            ))
+        }
+    };
 
     let mut equity_curve = Vec::with_capacity(n);
     let mut trades = Vec::new();
-    
+
     // 状态变量
     let mut position_size = 0.0; // 1.0 = Long, -1.0 = Short, 0.0 = Flat
-    let mut entry_price = 0.0;
+    let mut entry_price = 0.0;   // 含滑点的建仓成交价
     let mut entry_ts = 0;
-    let mut cash = 10000.0; // 初始资金，用于计算 equity 曲线趋势（相对值）
-    
+    let mut entry_bar = usize::MAX; // 建仓所在的 bar 下标，用于 next_open 下跳过当根 SL/TP
+    let mut qty = 0.0;           // 持仓数量 (按建仓时可用资金定量)
+    let mut entry_commission = 0.0; // 建仓手续费，平仓时并入该笔交易成本
+    let mut entry_slippage = 0.0;   // 建仓滑点成本
+    let mut highest_high = f64::NEG_INFINITY; // 建仓后最高价，用于多头移动止盈
+    let mut lowest_low = f64::INFINITY;       // 建仓后最低价，用于空头移动止盈
+    let mut cash = initial_cash; // 初始资金，equity 按 PnL 累加以实现复利
+
     for i in 0..n {
         let ts = timestamps[i];
         let op = opens[i];
@@ -290,11 +588,30 @@ fn simulate_trades_v2(
         let cl = closes[i];
         let sig = signals[i];
 
+        // 本根撮合所用的信号与参考价：
+        // - close 模式：执行 signals[i]，以 Close[i] 成交（保留原行为）
+        // - next_open 模式：执行 signals[i-1]，以 Open[i] 成交（最后一根的信号被丢弃，消除未来函数）
+        let (fill_sig, fill_px) = if next_open {
+            (if i > 0 { signals[i - 1] } else { 0 }, op)
+        } else {
+            (sig, cl)
+        };
+
+        // next_open 模式：信号先于 SL/TP 在本根 Open 撮合
+        if next_open {
+            execute_signal(
+                fill_sig, fill_px, hi, lo, ts, i, allow_short, commission_pct, slippage_pct,
+                &mut position_size, &mut entry_price, &mut entry_ts, &mut entry_bar, &mut qty,
+                &mut entry_commission, &mut entry_slippage, &mut highest_high, &mut lowest_low,
+                &mut cash, &mut trades,
+            );
+        }
+
         // 1. 检查当前持仓是否触发 SL/TP (Intra-bar check)
         // 假设顺序：Open -> Low/High -> Close
         // Conservative assumption: Check SL first using High/Low
-        
-        if position_size != 0.0 {
+        // next_open 下本根刚建仓的仓位，从下一根才开始做 intra-bar 检查
+        if position_size != 0.0 && entry_bar != i {
             let mut exit_price = 0.0;
             let mut reason = "".to_string();
             let mut triggered = false;
@@ -304,12 +621,20 @@ fn simulate_trades_v2(
                 let sl_price = entry_price * (1.0 - sl_pct);
                 let tp_price = entry_price * (1.0 + tp_pct);
 
+                // 移动止损价：建仓后最高价回撤 trail_pct
+                let trail_stop = if trail_pct > 0.0 { highest_high * (1.0 - trail_pct) } else { f64::NAN };
+
                 if lo <= sl_price {
                     // SL Hit
                     // 如果 Open 已经低于 SL (Gap Down)，则以 Open 成交，否则以 SL 价格成交
                     exit_price = if op < sl_price { op } else { sl_price };
                     reason = "sl".to_string();
                     triggered = true;
+                } else if !trail_stop.is_nan() && lo <= trail_stop {
+                    // Trailing stop hit (stop-before-target: 先于 TP 判定)
+                    exit_price = if op < trail_stop { op } else { trail_stop };
+                    reason = "trail".to_string();
+                    triggered = true;
                 } else if hi >= tp_price {
                     // TP Hit
                     // 如果 Open 已经高于 TP (Gap Up)，则以 Open 成交，否则以 TP 价格成交
@@ -322,11 +647,19 @@ fn simulate_trades_v2(
                 let sl_price = entry_price * (1.0 + sl_pct);
                 let tp_price = entry_price * (1.0 - tp_pct);
 
+                // 移动止损价：建仓后最低价反弹 trail_pct
+                let trail_stop = if trail_pct > 0.0 { lowest_low * (1.0 + trail_pct) } else { f64::NAN };
+
                 if hi >= sl_price {
                      // SL Hit
                     exit_price = if op > sl_price { op } else { sl_price };
                     reason = "sl".to_string();
                     triggered = true;
+                } else if !trail_stop.is_nan() && hi >= trail_stop {
+                    // Trailing stop hit (stop-before-target: 先于 TP 判定)
+                    exit_price = if op > trail_stop { op } else { trail_stop };
+                    reason = "trail".to_string();
+                    triggered = true;
                 } else if lo <= tp_price {
                     // TP Hit
                     exit_price = if op < tp_price { op } else { tp_price };
@@ -336,74 +669,60 @@ fn simulate_trades_v2(
             }
 
             if triggered {
-                // 执行平仓
-                let pnl = (exit_price - entry_price) * position_size;
-                cash += pnl;
-                trades.push((entry_ts, ts, entry_price, exit_price, pnl, reason));
+                // 执行平仓：成交价按滑点向不利方向调整，并计提平仓手续费
+                let exit_fill = if position_size > 0.0 {
+                    exit_price * (1.0 - slippage_pct)
+                } else {
+                    exit_price * (1.0 + slippage_pct)
+                };
+                let gross = (exit_fill - entry_price) * position_size * qty;
+                let exit_commission = commission_pct * (qty * exit_fill).abs();
+                let exit_slippage = qty * exit_price * slippage_pct;
+                cash += gross - exit_commission;
+                let commission = entry_commission + exit_commission;
+                let slippage = entry_slippage + exit_slippage;
+                let pnl = gross - commission;
+                trades.push((entry_ts, ts, entry_price, exit_fill, pnl, commission, slippage, reason));
                 position_size = 0.0;
                 entry_price = 0.0;
                 entry_ts = 0;
+                qty = 0.0;
             }
         }
 
         // 2. 处理新信号 (Signal Execution)
-        // 如果当前是 Flat，检查是否开仓
-        // 如果当前有持仓，检查是否反转 (Flip)
-        // 假设信号在 Close 时产生，下一个 Bar Open 执行？或者 Current Bar Close 执行？
-        // Vector backtest 常用逻辑：Signal at i, Execute at i (Close) or i+1 (Open).
-        // 这里为了简化且符合 bar 内撮合逻辑，假设：信号基于 Close 计算，在 NEXT BAR Open 执行？
-        // 但这里的输入是 aligned arrays。通常 signal[i] 意味着在 time i 产生的信号。
-        // 如果我们要在 time i 执行，意味着我们用 close[i] 成交？
-        // 
-        // 既然要做“Intra-bar SL/TP”，通常意味着 Entry 是在 Previous Bar Close 或 Current Bar Open。
-        // 为了最快模拟，我们假设：
-        // Signal[i] 导致在 Close[i] 成交 (简化) 或者我们模拟的是基于 i-1 的信号在 i 的行为？
-        // 
-        // User request: "传入 (timestamp, ..., signal, ...) ... O(N) loop".
-        // 让我们假设 signal[i] 是策略在 i 时刻给出的指令。
-        // 如果我们想在 i 时刻就进行 SL/TP 检查，那必须是 i-1 时刻建立的仓位。
-        // 
-        // 逻辑修正：
-        // Loop i:
-        //   First: Check intra-bar SL/TP for EXISTING position (from i-1).
-        //   Second: Process Signal[i] to Update position for NEXT step (or Close execute now).
-        //   If Signal[i] says Buy and we are Flat -> Open Long at Close[i].
-        //   If Signal[i] says Sell and we are Long -> Close Long at Close[i].
-        //   
-        // 这样 SL/TP 会在 持仓后的 每一个 Bar (i+1...) 进行检查。
-        
-        // 处理信号
-        if sig != 0 {
-             // 简化：全部按 Close 价成交
-             // 如果有反向持仓，先平仓
-             if position_size != 0.0 && ((sig == 1 && position_size == -1.0) || (sig == -1 && position_size == 1.0)) {
-                 let exit_price = cl;
-                 let pnl = (exit_price - entry_price) * position_size;
-                 cash += pnl;
-                 trades.push((entry_ts, ts, entry_price, exit_price, pnl, "signal_flip".to_string()));
-                 position_size = 0.0;
-             }
-
-             // 开新仓
-             if position_size == 0.0 {
-                 if sig == 1 {
-                     position_size = 1.0;
-                     entry_price = cl;
-                     entry_ts = ts;
-                 } else if sig == -1 {
-                     if allow_short {
-                         position_size = -1.0;
-                         entry_price = cl;
-                         entry_ts = ts;
-                     }
-                 }
-             }
+        // signal[i] 是策略在 time i（基于 Close[i]）产生的指令。
+        // close 模式：在 SL/TP 检查之后、以 Close[i] 撮合（信号与成交同根，隐含未来函数，保留为可选项）。
+        // next_open 模式：见上方，signal[i] 推迟到下一根 Open 撮合，从根本上消除该偏差。
+        if !next_open {
+            execute_signal(
+                fill_sig, fill_px, hi, lo, ts, i, allow_short, commission_pct, slippage_pct,
+                &mut position_size, &mut entry_price, &mut entry_ts, &mut entry_bar, &mut qty,
+                &mut entry_commission, &mut entry_slippage, &mut highest_high, &mut lowest_low,
+                &mut cash, &mut trades,
+            );
+        }
+
+        // 更新建仓后极值，供下一根 K 线的移动止损使用
+        // close 模式在本根 Close 建仓时，本根 High/Low 发生在建仓之前，极值从成交参考价起算
+        if position_size != 0.0 {
+            if !next_open && entry_bar == i {
+                highest_high = cl;
+                lowest_low = cl;
+            } else {
+                if hi > highest_high {
+                    highest_high = hi;
+                }
+                if lo < lowest_low {
+                    lowest_low = lo;
+                }
+            }
         }
 
         // 记录权益
         // Equity = Cash + Unrealized PnL
         let unrealized_pnl = if position_size != 0.0 {
-            (cl - entry_price) * position_size
+            (cl - entry_price) * position_size * qty
         } else {
             0.0
         };
@@ -413,6 +732,231 @@ fn simulate_trades_v2(
     Ok((equity_curve, trades))
 }
 
+// ===== Bill Williams 指标子系统（分形 / 动量震荡 / 鳄鱼线）=====
+
+/// 计算 Bill Williams 分形（fractals）。
+/// - high/low: 价格序列
+/// 返回 (up_fractals, down_fractals)。当 `high[i]` 严格大于两侧各两根的最高价时标记上分形；
+/// 当 `low[i]` 严格小于两侧各两根的最低价时标记下分形。两端各两根边界恒为 false。
+#[pyfunction]
+fn fractals(high: Vec<f64>, low: Vec<f64>) -> PyResult<(Vec<bool>, Vec<bool>)> {
+    let n = high.len().min(low.len());
+    let mut up = vec![false; n];
+    let mut down = vec![false; n];
+    for i in 2..n.saturating_sub(2) {
+        let h = high[i];
+        if h > high[i - 1] && h > high[i - 2] && h > high[i + 1] && h > high[i + 2] {
+            up[i] = true;
+        }
+        let l = low[i];
+        if l < low[i - 1] && l < low[i - 2] && l < low[i + 1] && l < low[i + 2] {
+            down[i] = true;
+        }
+    }
+    Ok((up, down))
+}
+
+/// 计算动量震荡指标（Awesome Oscillator）。
+/// - high/low: 价格序列
+/// - fast/slow: 快、慢周期（常用 5 / 34）
+/// 以中位价 (high+low)/2 的快周期 SMA 减去慢周期 SMA。
+#[pyfunction]
+#[pyo3(signature = (high, low, fast=5, slow=34))]
+fn awesome_oscillator(high: Vec<f64>, low: Vec<f64>, fast: usize, slow: usize) -> PyResult<Vec<f64>> {
+    if fast == 0 || slow == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "fast、slow 必须大于 0",
+        ));
+    }
+    let n = high.len().min(low.len());
+    let median: Vec<f64> = (0..n).map(|i| (high[i] + low[i]) / 2.0).collect();
+    let fast_ma = rolling_mean(&median, fast);
+    let slow_ma = rolling_mean(&median, slow);
+    let mut out = vec![f64::NAN; n];
+    for i in 0..n {
+        if !is_nan(fast_ma[i]) && !is_nan(slow_ma[i]) {
+            out[i] = fast_ma[i] - slow_ma[i];
+        }
+    }
+    Ok(out)
+}
+
+/// 将序列向前平移 shift 根（未来方向），前部用 NaN 填充。
+fn shift_forward(values: &[f64], shift: usize) -> Vec<f64> {
+    let n = values.len();
+    let mut out = vec![f64::NAN; n];
+    for i in shift..n {
+        out[i] = values[i - shift];
+    }
+    out
+}
+
+/// 计算鳄鱼线（Alligator）。
+/// - values: 输入序列（通常为中位价）
+/// - jaw/teeth/lips: 三条 SMMA 线的周期（常用 13 / 8 / 5）
+/// - jaw_shift/teeth_shift/lips_shift: 各线向前平移的根数（常用 8 / 5 / 3）
+/// 返回 (jaw, teeth, lips)，均为 Wilder/SMMA 平滑并按各自 shift 前移、前部 NaN 填充。
+#[pyfunction]
+#[pyo3(signature = (values, jaw=13, teeth=8, lips=5, jaw_shift=8, teeth_shift=5, lips_shift=3))]
+#[allow(clippy::too_many_arguments)]
+fn alligator(
+    values: Vec<f64>,
+    jaw: usize,
+    teeth: usize,
+    lips: usize,
+    jaw_shift: usize,
+    teeth_shift: usize,
+    lips_shift: usize,
+) -> PyResult<(Vec<f64>, Vec<f64>, Vec<f64>)> {
+    if jaw == 0 || teeth == 0 || lips == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "jaw、teeth、lips 必须大于 0",
+        ));
+    }
+    let jaw_line = shift_forward(&wilder_rma(&values, jaw), jaw_shift);
+    let teeth_line = shift_forward(&wilder_rma(&values, teeth), teeth_shift);
+    let lips_line = shift_forward(&wilder_rma(&values, lips), lips_shift);
+    Ok((jaw_line, teeth_line, lips_line))
+}
+
+/// 基于权益曲线与成交明细计算回测绩效指标。
+///
+/// Parameters
+/// ----------
+/// equity_curve: Vec<(ts, equity)>，一般取自 `simulate_trades_v2`
+/// trades: Vec<(entry_ts, exit_ts, entry_price, exit_price, pnl, commission, slippage, reason)>
+/// risk_free: 每期无风险收益率
+/// periods_per_year: 年化因子 (如日线 252、小时线 24*365)
+///
+/// Returns
+/// -------
+/// dict，包含 total_return / cagr / sharpe / sortino / max_drawdown /
+/// max_drawdown_duration / win_rate / profit_factor / avg_win / avg_loss / num_trades。
+#[pyfunction]
+fn performance_stats(
+    py: Python,
+    equity_curve: Vec<(i64, f64)>,
+    trades: Vec<(i64, i64, f64, f64, f64, f64, f64, String)>,
+    risk_free: f64,
+    periods_per_year: f64,
+) -> PyResult<PyObject> {
+    let out = PyDict::new(py);
+
+    let m = equity_curve.len();
+    // 逐期收益率
+    let mut returns = Vec::with_capacity(m.saturating_sub(1));
+    for i in 1..m {
+        let prev = equity_curve[i - 1].1;
+        let cur = equity_curve[i].1;
+        if prev != 0.0 {
+            returns.push(cur / prev - 1.0);
+        } else {
+            returns.push(0.0);
+        }
+    }
+
+    let first_eq = equity_curve.first().map(|p| p.1).unwrap_or(0.0);
+    let last_eq = equity_curve.last().map(|p| p.1).unwrap_or(0.0);
+
+    let total_return = if first_eq != 0.0 { last_eq / first_eq - 1.0 } else { 0.0 };
+    let cagr = if first_eq > 0.0 && last_eq > 0.0 && !returns.is_empty() {
+        (last_eq / first_eq).powf(periods_per_year / returns.len() as f64) - 1.0
+    } else {
+        f64::NAN
+    };
+
+    // Sharpe / Sortino
+    let (mut sharpe, mut sortino) = (f64::NAN, f64::NAN);
+    if !returns.is_empty() {
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        let excess = mean - risk_free;
+        if returns.len() > 1 {
+            let var = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>()
+                / (returns.len() as f64 - 1.0);
+            let std = var.sqrt();
+            if std > 0.0 {
+                sharpe = excess / std * periods_per_year.sqrt();
+            }
+        }
+        let downside_sq: f64 = returns
+            .iter()
+            .map(|r| if *r < 0.0 { r.powi(2) } else { 0.0 })
+            .sum::<f64>()
+            / returns.len() as f64;
+        let downside = downside_sq.sqrt();
+        if downside > 0.0 {
+            sortino = excess / downside * periods_per_year.sqrt();
+        }
+    }
+
+    // 最大回撤及其持续期（以 bar 计）
+    let mut peak = f64::NEG_INFINITY;
+    let mut max_dd = 0.0;
+    let mut peak_idx = 0usize;
+    let mut max_dd_duration = 0usize;
+    for (i, (_, eq)) in equity_curve.iter().enumerate() {
+        if *eq >= peak {
+            peak = *eq;
+            peak_idx = i;
+        }
+        if peak > 0.0 {
+            let dd = (peak - eq) / peak;
+            if dd > max_dd {
+                max_dd = dd;
+            }
+        }
+        let dur = i - peak_idx;
+        if dur > max_dd_duration {
+            max_dd_duration = dur;
+        }
+    }
+
+    // 成交统计
+    let num_trades = trades.len();
+    let mut wins = 0usize;
+    let mut gross_profit = 0.0;
+    let mut gross_loss = 0.0;
+    let mut sum_win = 0.0;
+    let mut sum_loss = 0.0;
+    let mut loss_count = 0usize;
+    for t in &trades {
+        let pnl = t.4;
+        if pnl > 0.0 {
+            wins += 1;
+            gross_profit += pnl;
+            sum_win += pnl;
+        } else if pnl < 0.0 {
+            gross_loss += -pnl;
+            sum_loss += -pnl;
+            loss_count += 1;
+        }
+    }
+    let win_rate = if num_trades > 0 { wins as f64 / num_trades as f64 } else { f64::NAN };
+    let profit_factor = if gross_loss > 0.0 {
+        gross_profit / gross_loss
+    } else if gross_profit > 0.0 {
+        f64::INFINITY
+    } else {
+        f64::NAN
+    };
+    let avg_win = if wins > 0 { sum_win / wins as f64 } else { f64::NAN };
+    let avg_loss = if loss_count > 0 { sum_loss / loss_count as f64 } else { f64::NAN };
+
+    out.set_item("total_return", total_return)?;
+    out.set_item("cagr", cagr)?;
+    out.set_item("sharpe", sharpe)?;
+    out.set_item("sortino", sortino)?;
+    out.set_item("max_drawdown", max_dd)?;
+    out.set_item("max_drawdown_duration", max_dd_duration)?;
+    out.set_item("win_rate", win_rate)?;
+    out.set_item("profit_factor", profit_factor)?;
+    out.set_item("avg_win", avg_win)?;
+    out.set_item("avg_loss", avg_loss)?;
+    out.set_item("num_trades", num_trades)?;
+
+    Ok(out.into_any().unbind())
+}
+
 /// Python 模块入口。
 #[pymodule]
 fn zenithalgo_rust(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
@@ -421,6 +965,13 @@ fn zenithalgo_rust(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(atr, m)?)?;
     m.add_function(wrap_pyfunction!(ema, m)?)?;
     m.add_function(wrap_pyfunction!(stddev, m)?)?;
+    m.add_function(wrap_pyfunction!(bbands, m)?)?;
+    m.add_function(wrap_pyfunction!(adx, m)?)?;
+    m.add_function(wrap_pyfunction!(kdj, m)?)?;
     m.add_function(wrap_pyfunction!(simulate_trades_v2, m)?)?;
+    m.add_function(wrap_pyfunction!(fractals, m)?)?;
+    m.add_function(wrap_pyfunction!(awesome_oscillator, m)?)?;
+    m.add_function(wrap_pyfunction!(alligator, m)?)?;
+    m.add_function(wrap_pyfunction!(performance_stats, m)?)?;
     Ok(())
 }